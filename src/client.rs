@@ -16,7 +16,11 @@ use websocket::{
     OwnedMessage,
 };
 
-use crate::shared::{GetFn, KVPair, Query, QueryType, Response};
+#[cfg(test)]
+use crate::shared::KVPair;
+use crate::shared::{GetFn, Query, QueryRes, QueryType, Response};
+#[cfg(test)]
+use serde_json::json;
 
 pub struct LVBClient {
     sender: Arc<Mutex<Writer<TcpStream>>>,
@@ -24,17 +28,21 @@ pub struct LVBClient {
 }
 
 pub struct RespWaiter {
-    pub rx: Receiver<Vec<KVPair>>,
+    pub rx: Receiver<QueryRes>,
     pub query_id: String,
     pub callbacks: CBMap,
     pub sender: Arc<Mutex<Writer<TcpStream>>>,
 }
 
-type CBMap = Arc<Mutex<HashMap<String, (bool, Sender<Vec<KVPair>>)>>>;
+type CBMap = Arc<Mutex<HashMap<String, (bool, Sender<QueryRes>)>>>;
 
 impl LVBClient {
     pub fn new(addr: &str) -> Self {
-        let addr = format!("ws://{addr}:3990");
+        Self::with_port(addr, 3990)
+    }
+
+    pub fn with_port(addr: &str, port: u16) -> Self {
+        let addr = format!("ws://{addr}:{port}");
 
         let client = client::ClientBuilder::new(&addr)
             .unwrap()
@@ -53,27 +61,38 @@ impl LVBClient {
         }
     }
 
-    pub fn insert<T: Serialize>(&self, key: &str, value: T) {
-        let query_id = Uuid::new_v4();
-
-        let json_str = serde_json::to_string(&value).unwrap();
-        let value = Value::from_str(&json_str).unwrap();
+    /// Inserts `value` and responds with `QueryRes::Ack` once it is stored.
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) -> RespWaiter {
+        self.request(QueryType::INSERT(key.into(), to_value(value)), false)
+    }
 
-        let query = Query {
-            query_type: QueryType::INSERT(key.into(), value),
-            query_id: query_id.to_string(),
-        };
+    /// Inserts `value` and responds with the value it replaced, if any.
+    pub fn insert_returning<T: Serialize>(&self, key: &str, value: T) -> RespWaiter {
+        self.request(
+            QueryType::INSERT_RETURNING(key.into(), to_value(value)),
+            false,
+        )
+    }
 
-        let query_str = serde_json::to_string(&query).unwrap();
+    /// Deletes `key` and responds with `QueryRes::Ack` once it is removed.
+    pub fn delete(&self, key: &str) -> RespWaiter {
+        self.request(QueryType::DELETE(key.into()), false)
+    }
 
-        self.sender
-            .lock()
-            .unwrap()
-            .send_message(&OwnedMessage::Text(query_str))
-            .unwrap();
+    /// Deletes `key` and responds with the value it held, if any.
+    pub fn delete_returning(&self, key: &str) -> RespWaiter {
+        self.request(QueryType::DELETE_RETURNING(key.into()), false)
     }
 
     pub fn get(&self, search: GetFn) -> RespWaiter {
+        self.request(QueryType::GET(search), false)
+    }
+
+    pub fn watch(&self, search: GetFn) -> RespWaiter {
+        self.request(QueryType::WATCH(search), true)
+    }
+
+    fn request(&self, query_type: QueryType, persist: bool) -> RespWaiter {
         let (sx, rx) = unbounded();
 
         let query_id = Uuid::new_v4();
@@ -81,20 +100,15 @@ impl LVBClient {
         self.callbacks
             .lock()
             .unwrap()
-            .insert(query_id.to_string(), (false, sx));
+            .insert(query_id.to_string(), (persist, sx));
 
         let query = Query {
-            query_type: QueryType::GET(search),
+            query_type,
             query_id: query_id.to_string(),
         };
 
-        let query_str = serde_json::to_string(&query).unwrap();
+        self.send_query(&query);
 
-        self.sender
-            .lock()
-            .unwrap()
-            .send_message(&OwnedMessage::Text(query_str))
-            .unwrap();
         RespWaiter {
             rx,
             query_id: query_id.to_string(),
@@ -103,38 +117,22 @@ impl LVBClient {
         }
     }
 
-    pub fn watch(&self, search: GetFn) -> RespWaiter {
-        let (sx, rx) = unbounded();
-
-        let query_id = Uuid::new_v4();
-
-        self.callbacks
-            .lock()
-            .unwrap()
-            .insert(query_id.to_string(), (true, sx));
-
-        let query = Query {
-            query_type: QueryType::WATCH(search),
-            query_id: query_id.to_string(),
-        };
-
-        let query_str = serde_json::to_string(&query).unwrap();
+    fn send_query(&self, query: &Query) {
+        let query_str = serde_json::to_string(query).unwrap();
 
         self.sender
             .lock()
             .unwrap()
             .send_message(&OwnedMessage::Text(query_str))
             .unwrap();
-
-        RespWaiter {
-            rx,
-            query_id: query_id.to_string(),
-            callbacks: self.callbacks.clone(),
-            sender: self.sender.clone(),
-        }
     }
 }
 
+fn to_value<T: Serialize>(value: T) -> Value {
+    let json_str = serde_json::to_string(&value).unwrap();
+    Value::from_str(&json_str).unwrap()
+}
+
 fn run_socket(mut reader: Reader<TcpStream>, callbacks: CBMap) {
     while let Result::Ok(msg) = reader.recv_message() {
         match msg {
@@ -179,6 +177,82 @@ fn insert_test() {
     );
 }
 
+/// Removes the test database directory when dropped.
+#[cfg(test)]
+struct TempDb(std::path::PathBuf);
+
+#[cfg(test)]
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Starts a server with a fresh database on an unused port and connects to it.
+#[cfg(test)]
+fn local_client() -> (LVBClient, TempDb) {
+    use std::{net::TcpListener, time::Duration};
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let db = TempDb(std::env::temp_dir().join(format!("livebucket-{}", Uuid::new_v4())));
+
+    let path = db.0.clone();
+    thread::spawn(move || crate::server::run_on_port(&path, port, &[]));
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    (LVBClient::with_port("127.0.0.1", port), db)
+}
+
+#[test]
+fn insert_returning_test() {
+    let (client, _db) = local_client();
+    let key = Uuid::new_v4().to_string();
+
+    let first = client.insert_returning(&key, json!({"name": "jens"}));
+    assert!(matches!(first.recv().unwrap(), QueryRes::Single(None)));
+
+    let second = client.insert_returning(&key, json!({"name": "thor"}));
+    assert!(matches!(
+        second.recv().unwrap(),
+        QueryRes::Single(Some(KVPair { value, .. })) if value == json!({"name": "jens"})
+    ));
+}
+
+#[test]
+fn insert_ack_test() {
+    let (client, _db) = local_client();
+    let key = Uuid::new_v4().to_string();
+
+    let inserted = client.insert(&key, json!({"name": "jens"}));
+    assert!(matches!(inserted.recv().unwrap(), QueryRes::Ack));
+
+    let deleted = client.delete(&key);
+    assert!(matches!(deleted.recv().unwrap(), QueryRes::Ack));
+}
+
+#[test]
+fn delete_returning_test() {
+    let (client, _db) = local_client();
+    let key = Uuid::new_v4().to_string();
+
+    client.insert(&key, json!(1234)).recv().unwrap();
+
+    let deleted = client.delete_returning(&key);
+    assert!(matches!(
+        deleted.recv().unwrap(),
+        QueryRes::Single(Some(KVPair { value, .. })) if value == json!(1234)
+    ));
+
+    let deleted = client.delete_returning(&key);
+    assert!(matches!(deleted.recv().unwrap(), QueryRes::Single(None)));
+}
+
 #[test]
 fn get_test() {
     let client = LVBClient::new("jensogkarsten.site");
@@ -188,8 +262,33 @@ fn get_test() {
     println!("{:#?}", rx.recv().unwrap());
 }
 
+#[test]
+fn get_key_test() {
+    let (client, _db) = local_client();
+    let key = Uuid::new_v4().to_string();
+
+    let rx = client.get(GetFn::Key(key.clone()));
+    assert!(matches!(rx.recv().unwrap(), QueryRes::Single(None)));
+
+    client.insert(&key, json!("jens")).recv().unwrap();
+
+    let rx = client.get(GetFn::Key(key.clone()));
+    assert!(matches!(
+        rx.recv().unwrap(),
+        QueryRes::Single(Some(KVPair { value, .. })) if value == json!("jens")
+    ));
+}
+
+#[test]
+fn unknown_procedure_test() {
+    let (client, _db) = local_client();
+
+    let rx = client.get(GetFn::Procedure("no_such_fn".into(), Value::Null));
+    assert!(matches!(rx.recv().unwrap(), QueryRes::Error(_)));
+}
+
 impl Deref for RespWaiter {
-    type Target = Receiver<Vec<KVPair>>;
+    type Target = Receiver<QueryRes>;
 
     fn deref(&self) -> &Self::Target {
         &self.rx
@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     net::TcpStream,
     path::Path,
@@ -8,20 +9,26 @@ use std::{
 
 use serde::de::DeserializeOwned;
 use serde_json::Value;
-use sled::Db;
+use sled::{Db, IVec};
 use uuid::Uuid;
 use websocket::{
     sync::{Client, Writer},
     OwnedMessage,
 };
 
-use crate::shared::{GetFn, KVPair, Query, QueryType, Response};
+use crate::shared::{GetFn, KVPair, Query, QueryRes, QueryType, Response};
 
-pub fn run(path: &Path, functions: &'static [(&'static str, fn(DBRead, Value) -> Vec<KVPair>)]) {
-    let mut server = websocket::server::sync::Server::bind("0.0.0.0:3990").unwrap();
+pub type Procedures = &'static [(&'static str, fn(DBRead, Value) -> Vec<KVPair>)];
 
+pub fn run(path: &Path, functions: Procedures) {
+    run_on_port(path, 3990, functions)
+}
+
+pub fn run_on_port(path: &Path, port: u16, functions: Procedures) {
     let db = sled::open(path).unwrap();
 
+    let mut server = websocket::server::sync::Server::bind(("0.0.0.0", port)).unwrap();
+
     let (sx, rx) = channel();
     let sx_c = sx.clone();
     thread::spawn(move || server_event_handler(db, rx, sx_c, functions));
@@ -42,7 +49,7 @@ fn server_event_handler(
     db: Db,
     rx: Receiver<ServerEvent>,
     event_sx: Sender<ServerEvent>,
-    functions: &'static [(&'static str, fn(DBRead, Value) -> Vec<KVPair>)],
+    functions: Procedures,
 ) {
     let mut clients = HashMap::new();
     let mut watches = vec![];
@@ -60,32 +67,28 @@ fn server_event_handler(
                 QueryType::GET(search) => {
                     let query_res = match search {
                         GetFn::Procedure(fn_name, arg) => {
-                            let Some(fn_) = functions.iter().find(|(f, _)| f == &fn_name) else {
-                                eprintln!("TODO: Handle invalid function name");
-                                continue;
-                            };
-
-                            fn_.1(DBRead::new(db.clone()), arg)
+                            match functions.iter().find(|(f, _)| f == &fn_name) {
+                                Some(fn_) => QueryRes::Many(fn_.1(DBRead::new(db.clone()), arg)),
+                                None => QueryRes::Error(format!("No procedure named {fn_name}")),
+                            }
+                        }
+                        GetFn::Prefix(search) => QueryRes::Many(get_query(&search, &db)),
+                        GetFn::Key(key) => {
+                            let value = db
+                                .get(&key)
+                                .map_err(|err| format!("Failed fetching {key} from db: {err}"));
+                            if let Err(err) = &value {
+                                eprintln!("{err}");
+                            }
+                            single_res(key, value)
                         }
-                        GetFn::Prefix(search) => get_query(&search, &db),
                     };
 
-                    let Some(sx) = clients.get_mut(&client_id) else {
-                        eprintln!("Failed getting sx of {client_id}");
-                        continue;
-                    };
                     let resp = Response {
                         query_id: query.query_id,
                         query_res,
                     };
-
-                    let Result::Ok(resp_text) = serde_json::to_string(&resp) else {
-                        eprintln!("Failed to serialize response {resp:#?}");
-                        continue;
-                    };
-                    if let Err(_) = sx.send_message(&OwnedMessage::Text(resp_text)) {
-                        clients.remove(&client_id);
-                    }
+                    send_response(&mut clients, client_id, resp);
                 }
                 QueryType::WATCH(search) => {
                     watches.push((client_id, query.query_id.clone(), search.clone()));
@@ -102,32 +105,32 @@ fn server_event_handler(
                     }
                 }
                 QueryType::INSERT(key, value) => {
-                    let Result::Ok(ser_json) = serde_json::to_string(&value) else {
-                        eprintln!("Failed to serialize {value:#?}");
-                        continue;
-                    };
-                    if let Err(insert_err) = db.insert(&key, ser_json.as_str()) {
-                        eprintln!("Failed to insert {key}:{ser_json} into db: {insert_err:?}");
-                        continue;
+                    let res = insert_query(&key, &value, &db);
+                    if res.is_ok() {
+                        notify_watches(&watches, &key, &event_sx);
                     }
-                    for (client_id, id, search) in &watches {
-                        if let GetFn::Procedure(search, _) = search {
-                            if !search.starts_with(&key) {
-                                continue;
-                            }
-                        }
-
-                        if let Err(err) = event_sx.send(ServerEvent::Query(
-                            *client_id,
-                            Query {
-                                query_type: QueryType::GET(search.to_owned()),
-                                query_id: id.to_owned(),
-                            },
-                        )) {
-                            eprintln!("Failed to self-send watch update {search:?} with: {err:?}");
-                            continue;
-                        }
+                    send_ack(&mut clients, client_id, query.query_id, res);
+                }
+                QueryType::INSERT_RETURNING(key, value) => {
+                    let previous = insert_query(&key, &value, &db);
+                    if previous.is_ok() {
+                        notify_watches(&watches, &key, &event_sx);
                     }
+                    send_previous(&mut clients, client_id, query.query_id, key, previous);
+                }
+                QueryType::DELETE(key) => {
+                    let res = delete_query(&key, &db);
+                    if res.is_ok() {
+                        notify_watches(&watches, &key, &event_sx);
+                    }
+                    send_ack(&mut clients, client_id, query.query_id, res);
+                }
+                QueryType::DELETE_RETURNING(key) => {
+                    let previous = delete_query(&key, &db);
+                    if previous.is_ok() {
+                        notify_watches(&watches, &key, &event_sx);
+                    }
+                    send_previous(&mut clients, client_id, query.query_id, key, previous);
                 }
                 QueryType::UNWATCH => watches.retain(|(_, q, _)| q != &query.query_id),
             },
@@ -146,12 +149,7 @@ fn get_query(search: &str, db: &Db) -> Vec<KVPair> {
             eprintln!("Failed converting db key {key:?} to string");
             continue;
         };
-        let Result::Ok(json_str) = String::from_utf8(value.to_vec()) else {
-            eprintln!("Failed converting db value {value:?} to string");
-            continue;
-        };
-        let Result::Ok(value) = serde_json::from_str(&json_str) else {
-            eprintln!("Failed to parse {json_str} to json value");
+        let Some(value) = parse_db_value(&value) else {
             continue;
         };
 
@@ -161,6 +159,171 @@ fn get_query(search: &str, db: &Db) -> Vec<KVPair> {
     res
 }
 
+fn insert_query(key: &str, value: &Value, db: &Db) -> Result<Option<IVec>, String> {
+    let previous = serde_json::to_string(value)
+        .map_err(|err| format!("Failed to serialize {value}: {err}"))
+        .and_then(|ser_json| {
+            db.insert(key, encode_value(ser_json))
+                .map_err(|err| format!("Failed to insert {key} into db: {err}"))
+        });
+    if let Err(err) = &previous {
+        eprintln!("{err}");
+    }
+    previous
+}
+
+fn delete_query(key: &str, db: &Db) -> Result<Option<IVec>, String> {
+    let previous = db
+        .remove(key)
+        .map_err(|err| format!("Failed to remove {key} from db: {err}"));
+    if let Err(err) = &previous {
+        eprintln!("{err}");
+    }
+    previous
+}
+
+fn parse_db_value(value: &[u8]) -> Option<Value> {
+    let data = decode_value(value)?;
+    let Result::Ok(value) = serde_json::from_slice(&data) else {
+        eprintln!(
+            "Failed to parse {:?} to json value",
+            String::from_utf8_lossy(&data)
+        );
+        return None;
+    };
+    Some(value)
+}
+
+fn send_response(
+    clients: &mut HashMap<ClientID, Writer<TcpStream>>,
+    client_id: ClientID,
+    resp: Response,
+) {
+    let Some(sx) = clients.get_mut(&client_id) else {
+        eprintln!("Failed getting sx of {client_id}");
+        return;
+    };
+    let Result::Ok(resp_text) = serde_json::to_string(&resp) else {
+        eprintln!("Failed to serialize response {resp:#?}");
+        return;
+    };
+    if sx.send_message(&OwnedMessage::Text(resp_text)).is_err() {
+        clients.remove(&client_id);
+    }
+}
+
+/// Responds to a plain INSERT or DELETE with whether it succeeded.
+fn send_ack(
+    clients: &mut HashMap<ClientID, Writer<TcpStream>>,
+    client_id: ClientID,
+    query_id: String,
+    res: Result<Option<IVec>, String>,
+) {
+    let query_res = match res {
+        Result::Ok(_) => QueryRes::Ack,
+        Err(err) => QueryRes::Error(err),
+    };
+    let resp = Response {
+        query_id,
+        query_res,
+    };
+    send_response(clients, client_id, resp);
+}
+
+/// Responds to a returning INSERT or DELETE with the value it replaced.
+fn send_previous(
+    clients: &mut HashMap<ClientID, Writer<TcpStream>>,
+    client_id: ClientID,
+    query_id: String,
+    key: String,
+    previous: Result<Option<IVec>, String>,
+) {
+    let resp = Response {
+        query_id,
+        query_res: single_res(key, previous),
+    };
+    send_response(clients, client_id, resp);
+}
+
+/// A present but unreadable value is an error rather than `Single(None)`.
+fn single_res(key: String, value: Result<Option<IVec>, String>) -> QueryRes {
+    match value {
+        Result::Ok(None) => QueryRes::Single(None),
+        Result::Ok(Some(value)) => match parse_db_value(&value) {
+            Some(value) => QueryRes::Single(Some(KVPair { key, value })),
+            None => QueryRes::Error(format!("Failed to read value of {key}")),
+        },
+        Err(err) => QueryRes::Error(err),
+    }
+}
+
+fn notify_watches(
+    watches: &[(ClientID, String, GetFn)],
+    key: &str,
+    event_sx: &Sender<ServerEvent>,
+) {
+    for (client_id, id, search) in watches {
+        if let GetFn::Procedure(search, _) = search {
+            if !search.starts_with(key) {
+                continue;
+            }
+        }
+        if let GetFn::Key(search) = search {
+            if search != key {
+                continue;
+            }
+        }
+
+        if let Err(err) = event_sx.send(ServerEvent::Query(
+            *client_id,
+            Query {
+                query_type: QueryType::GET(search.to_owned()),
+                query_id: id.to_owned(),
+            },
+        )) {
+            eprintln!("Failed to self-send watch update {search:?} with: {err:?}");
+            continue;
+        }
+    }
+}
+
+/// First byte of a value stored zstd-compressed. Uncompressed values are plain
+/// json text, which never starts with a NUL byte, so both can live side by side.
+const COMPRESSED_MARKER: u8 = 0;
+/// Values with a serialized size above this many bytes are compressed.
+#[cfg(feature = "compression")]
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+fn encode_value(json_str: String) -> Vec<u8> {
+    #[cfg(feature = "compression")]
+    if json_str.len() > COMPRESSION_THRESHOLD {
+        match zstd::encode_all(json_str.as_bytes(), 0) {
+            Result::Ok(compressed) if compressed.len() + 1 < json_str.len() => {
+                let mut data = Vec::with_capacity(compressed.len() + 1);
+                data.push(COMPRESSED_MARKER);
+                data.extend(compressed);
+                return data;
+            }
+            Result::Ok(_) => {}
+            Err(err) => eprintln!("Failed to compress value, storing it uncompressed: {err:?}"),
+        }
+    }
+    json_str.into_bytes()
+}
+
+fn decode_value(data: &[u8]) -> Option<Cow<'_, [u8]>> {
+    let Some((&COMPRESSED_MARKER, compressed)) = data.split_first() else {
+        return Some(Cow::Borrowed(data));
+    };
+    match zstd::decode_all(compressed) {
+        Result::Ok(decompressed) => Some(Cow::Owned(decompressed)),
+        Err(err) => {
+            eprintln!("Failed to decompress db value: {err:?}");
+            None
+        }
+    }
+}
+
 type ClientID = Uuid;
 enum ServerEvent {
     ClientConnected(ClientID, Writer<TcpStream>),
@@ -218,7 +381,7 @@ impl DBRead {
 
     pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
         let data = self.db.get(key).ok()??;
-        let t = serde_json::from_slice(&data).ok()?;
+        let t = serde_json::from_slice(&decode_value(&data)?).ok()?;
         Some(t)
     }
     pub fn get_prefix_parsed<T: DeserializeOwned>(&self, prefix: &str) -> Vec<(String, T)> {
@@ -228,7 +391,7 @@ impl DBRead {
             .filter_map(|(key, value)| {
                 Some((
                     String::from_utf8(key.to_vec()).ok()?,
-                    serde_json::from_slice(&value).ok()?,
+                    serde_json::from_slice(&decode_value(&value)?).ok()?,
                 ))
             })
             .collect()
@@ -241,6 +404,19 @@ impl DBRead {
     }
 }
 
+#[test]
+fn value_encoding_test() {
+    let small = r#"{"name":"thor"}"#;
+    assert_eq!(encode_value(small.to_owned()), small.as_bytes());
+    assert_eq!(decode_value(small.as_bytes()).unwrap(), small.as_bytes());
+
+    let large = serde_json::to_string(&vec!["jens og karsten"; 500]).unwrap();
+    let encoded = encode_value(large.clone());
+    #[cfg(feature = "compression")]
+    assert_eq!(encoded[0], COMPRESSED_MARKER);
+    assert_eq!(decode_value(&encoded).unwrap(), large.as_bytes());
+}
+
 #[test]
 fn insert_test() {
     use serde_json::json;
@@ -4,14 +4,21 @@ use serde_json::Value;
 pub enum GetFn {
     Procedure(String, Value),
     Prefix(String),
+    Key(String),
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[allow(non_camel_case_types)]
 pub enum QueryType {
     GET(GetFn),
     WATCH(GetFn),
     UNWATCH,
     INSERT(String, Value),
+    /// Like INSERT, but responds with the value it replaced.
+    INSERT_RETURNING(String, Value),
+    DELETE(String),
+    /// Like DELETE, but responds with the value it removed.
+    DELETE_RETURNING(String),
 }
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct Query {
@@ -22,7 +29,19 @@ pub struct Query {
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct Response {
     pub query_id: String,
-    pub query_res: Vec<KVPair>,
+    pub query_res: QueryRes,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub enum QueryRes {
+    /// Result of a point lookup. `None` if the key is not present.
+    Single(Option<KVPair>),
+    Many(Vec<KVPair>),
+    /// Reserved for counting queries such as EXISTS/COUNT, no query produces it yet.
+    Count(usize),
+    /// The query succeeded but has nothing to return.
+    Ack,
+    Error(String),
 }
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct KVPair {